@@ -1,9 +1,22 @@
+use std::collections::hash_map::DefaultHasher;
+use std::fmt;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
 use failure::format_err;
 use reqwest::header::{
-    HeaderMap, HeaderName, HeaderValue, ACCEPT_RANGES, AUTHORIZATION, RANGE, USER_AGENT,
+    HeaderMap, HeaderName, HeaderValue, ACCEPT_RANGES, AUTHORIZATION, ETAG, IF_MODIFIED_SINCE,
+    IF_NONE_MATCH, IF_RANGE, LAST_MODIFIED, RANGE, RETRY_AFTER, USER_AGENT,
 };
-use reqwest::Client;
+use reqwest::{Client, Response};
 use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
 use serde_json::{from_str, Value};
 
 use crate::model::Auth;
@@ -11,48 +24,180 @@ use crate::result::Result;
 
 const DEFAULT_UA: &str = "Mozilla/5.0 (Windows NT 6.1; WOW64) AppleWebKit/537.21 (KHTML, like Gecko) Mwendo/1.1.5 Safari/537.21";
 const CHUNK: u64 = 2 * 1024 * 1024;
+/// Number of ranged `GET`s kept in flight at once by `get_as_data`.
+const DEFAULT_POOL_SIZE: usize = 6;
+
+/// Shared flag used to abort an in-flight [`HttpClient::get_as_data`] call.
+///
+/// Cloning a `CancelToken` keeps it pointing at the same underlying flag, so the
+/// caller can hold one end and set it (e.g. from a Ctrl-C handler) while the
+/// download thread polls the other end.
+pub type CancelToken = Arc<AtomicBool>;
+
+/// Returns a fresh, unset [`CancelToken`].
+pub fn new_cancel_token() -> CancelToken {
+    Arc::new(AtomicBool::new(false))
+}
+
+/// Error returned by [`HttpClient::get_as_data`] when `cancel` was observed set.
+#[derive(Debug)]
+pub struct Cancelled;
+
+impl fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Download was cancelled")
+    }
+}
+
+impl std::error::Error for Cancelled {}
+
+/// Backoff policy applied when a request fails with a connection error, a timeout,
+/// or a `429`/`5xx` response.
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: 4,
+            base_delay: Duration::from_millis(250),
+            max_delay: Duration::from_secs(10),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Exponential backoff for `attempt` (0-based), capped at `max_delay` and with a
+    /// little jitter mixed in so a batch of requests doesn't retry in lockstep.
+    fn delay_for(&self, attempt: u32) -> Duration {
+        let exp_ms = self.base_delay.as_millis() as u64 * 2u64.saturating_pow(attempt.min(16));
+        let capped_ms = exp_ms.min(self.max_delay.as_millis() as u64);
+        Duration::from_millis(capped_ms + jitter_ms(capped_ms.max(1)))
+    }
+}
+
+/// Timeouts and retry behavior for a [`UdemyHttpClient`]; see [`UdemyHttpClient::with_config`].
+#[derive(Debug, Clone)]
+pub struct HttpClientConfig {
+    pub connect_timeout: Duration,
+    pub read_timeout: Duration,
+    pub retry: RetryPolicy,
+    /// Directory used to cache conditionally-revalidated `GET` bodies (see
+    /// `HttpClient::get_as_text`). `None` (the default) disables the cache.
+    pub cache_dir: Option<PathBuf>,
+}
+
+impl Default for HttpClientConfig {
+    fn default() -> HttpClientConfig {
+        HttpClientConfig {
+            connect_timeout: Duration::from_secs(10),
+            read_timeout: Duration::from_secs(30),
+            retry: RetryPolicy::default(),
+            cache_dir: None,
+        }
+    }
+}
+
+/// On-disk record of a cached response, revalidated with `If-None-Match`/`If-Modified-Since`.
+#[derive(Debug, Serialize, Deserialize)]
+struct CacheEntry {
+    etag: Option<String>,
+    last_modified: Option<String>,
+    body: String,
+}
+
+/// Cheap, dependency-free source of retry jitter in `[0, max_ms)`.
+fn jitter_ms(max_ms: u64) -> u64 {
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| u64::from(d.subsec_nanos()))
+        .unwrap_or(0);
+    nanos % max_ms
+}
 
 pub struct UdemyHttpClient {
     client: Client,
+    pool_size: usize,
+    retry: RetryPolicy,
+    cache_dir: Option<PathBuf>,
 }
 
 pub trait HttpClient {
     fn get_as_text(&self, url: &str, auth: &Auth) -> Result<String>;
+    /// Like `get_as_text`, but skips reading the cache for this one call — the fresh
+    /// response is still written back, so the cache stays warm for other callers. Use
+    /// this to force a refetch of a single URL without disabling the cache entirely.
+    fn get_as_text_bypass_cache(&self, url: &str, auth: &Auth) -> Result<String>;
     fn get_as_json(&self, url: &str, auth: &Auth) -> Result<Value> {
         self.get_as_text(url, auth).map(|text| {
             from_str(text.as_str())
                 .map_err(|e| format_err!("Error parsing json from url <{}>: {:?}", url, e))
         })?
     }
-    fn get_as_data(&self, url: &str, f: &mut dyn FnMut(u64)) -> Result<Vec<u8>>;
+    fn get_as_data(
+        &self,
+        url: &str,
+        cancel: &CancelToken,
+        f: &(dyn Fn(u64) + Send + Sync),
+    ) -> Result<Vec<u8>>;
+    /// Streams `url` straight into `writer` instead of buffering it in memory, so
+    /// downloading a multi-GB lecture doesn't require holding it in a `Vec<u8>`. Ranges
+    /// are still dispatched across `self.pool_size` workers, same as `get_as_data`; each
+    /// batch is written out to `writer` in offset order as soon as it completes.
+    ///
+    /// `resume` controls whether this continues a previous, interrupted call. Callers
+    /// who want the old `Vec<u8>` behavior can get it back with `get_as_data_to(url,
+    /// cancel, &mut Cursor::new(Vec::new()), Resume::Fresh, f)`.
+    fn get_as_data_to(
+        &self,
+        url: &str,
+        cancel: &CancelToken,
+        writer: &mut dyn Write,
+        resume: Resume,
+        f: &(dyn Fn(u64) + Send + Sync),
+    ) -> Result<()>;
+    /// Returns the resource's current `ETag` (preferred) or `Last-Modified` header, if
+    /// any, for callers to persist alongside a partial download and later hand back to
+    /// `get_as_data_to` via `Resume::Resumed` to validate the resource hasn't changed.
+    fn get_resource_validator(&self, url: &str) -> Result<Option<String>>;
     fn get_content_length(&self, url: &str) -> Result<u64>;
     fn post_json(&self, url: &str, json: &Value, auth: &Auth) -> Result<()>;
 }
 
+/// How `get_as_data_to` should continue (or not) a previous partial download.
+pub enum Resume {
+    /// Download the whole resource from the start.
+    Fresh,
+    /// Continue a partial download that already wrote `bytes_written` bytes to the
+    /// writer, validated against `etag_or_last_modified` (captured via
+    /// `get_resource_validator` when that partial download began) using `If-Range`.
+    /// If the resource has since changed, the server will ignore the `Range` and send
+    /// the full body back, which `get_as_data_to` treats as an error rather than
+    /// splicing stale and fresh bytes together — callers should restart with
+    /// `Resume::Fresh` in that case.
+    Resumed {
+        bytes_written: u64,
+        etag_or_last_modified: String,
+    },
+}
+
 impl HttpClient for UdemyHttpClient {
     fn get_as_text(&self, url: &str, auth: &Auth) -> Result<String> {
-        let mut resp = self
-            .client
-            .get(url)
-            .headers(self.construct_headers(auth))
-            .send()?;
-        if resp.status().is_success() {
-            Ok(resp.text()?)
-        } else {
-            Err(format_err!(
-                "Error while getting from url <{}>: <{}>",
-                url,
-                resp.status()
-            ))
-        }
+        self.get_as_text_with_cache(url, auth, true)
+    }
+
+    fn get_as_text_bypass_cache(&self, url: &str, auth: &Auth) -> Result<String> {
+        self.get_as_text_with_cache(url, auth, false)
     }
 
     fn get_content_length(&self, url: &str) -> Result<u64> {
-        let resp = self
-            .client
-            .head(url)
-            // .headers(self.construct_headers())
-            .send()?;
+        let resp = self.send_retrying(|| self.client.head(url), None)?;
         if resp.status().is_success() {
             Ok(resp
                 .content_length()
@@ -66,47 +211,60 @@ impl HttpClient for UdemyHttpClient {
         }
     }
 
-    fn get_as_data(&self, url: &str, f: &mut dyn FnMut(u64)) -> Result<Vec<u8>> {
+    fn get_as_data(
+        &self,
+        url: &str,
+        cancel: &CancelToken,
+        f: &(dyn Fn(u64) + Send + Sync),
+    ) -> Result<Vec<u8>> {
         let http_range = self.has_http_range(url)?;
         if http_range {
             let total = self.get_content_length(url)?;
-            let mut offset = 0_u64;
-            let mut buf = Vec::with_capacity(total as usize);
+            let ranges = Self::build_ranges(total);
+            // `buf` may already hold bytes from ranges that completed before a cancellation
+            // or error; in that case it is dropped here and nothing partial is handed back
+            // to the caller.
+            let mut buf = vec![0_u8; total as usize];
+            let fetched = AtomicU64::new(0);
 
-            loop {
-                let mut temp_buf = Vec::with_capacity(CHUNK as usize);
-                let mut resp = self
-                    .client
-                    .get(url)
-                    .header(RANGE, format!("bytes={}-{}", offset, offset + CHUNK - 1))
-                    .send()?;
-                match resp.status() {
-                    StatusCode::PARTIAL_CONTENT => {
-                        resp.copy_to(&mut temp_buf)?;
-                        buf.append(&mut temp_buf);
-                        (*f)(offset + CHUNK);
-
-                        offset += CHUNK;
-                        if offset > total {
-                            break;
-                        }
-                    }
-                    StatusCode::OK => {
-                        resp.copy_to(&mut buf)?;
-                        break;
-                    }
-                    _ => {
-                        return Err(format_err!("Error received {:?}", resp.status()));
-                    }
+            for batch in ranges.chunks(self.pool_size) {
+                if cancel.load(Ordering::Relaxed) {
+                    return Err(Cancelled.into());
                 }
+
+                thread::scope(|scope| -> Result<()> {
+                    let handles: Vec<_> = batch
+                        .iter()
+                        .map(|&(start, end)| {
+                            scope.spawn(move || {
+                                if cancel.load(Ordering::Relaxed) {
+                                    return Err(Cancelled.into());
+                                }
+                                let data = self.fetch_range_with_retry(url, start, end, cancel, None)?;
+                                fetched.fetch_add(data.len() as u64, Ordering::Relaxed);
+                                f(fetched.load(Ordering::Relaxed));
+                                Result::Ok((start, data))
+                            })
+                        })
+                        .collect();
+
+                    for handle in handles {
+                        let (start, data) = handle
+                            .join()
+                            .map_err(|_| format_err!("Worker thread panicked while downloading <{}>", url))??;
+                        let start = start as usize;
+                        buf[start..start + data.len()].copy_from_slice(&data);
+                    }
+                    Ok(())
+                })?;
             }
             Ok(buf)
         } else {
-            let mut resp = self.client.get(url).send()?;
+            let mut resp = self.send_retrying(|| self.client.get(url), Some(cancel))?;
             if resp.status().is_success() {
                 let mut buf: Vec<u8> = vec![];
                 let size = resp.copy_to(&mut buf)?;
-                (*f)(size);
+                f(size);
                 Ok(buf)
             } else {
                 Err(format_err!("Error while getting from url <{}>", url))
@@ -114,28 +272,367 @@ impl HttpClient for UdemyHttpClient {
         }
     }
 
+    fn get_as_data_to(
+        &self,
+        url: &str,
+        cancel: &CancelToken,
+        writer: &mut dyn Write,
+        resume: Resume,
+        f: &(dyn Fn(u64) + Send + Sync),
+    ) -> Result<()> {
+        let (mut written, if_range) = match resume {
+            Resume::Fresh => (0_u64, None),
+            Resume::Resumed {
+                bytes_written,
+                etag_or_last_modified,
+            } => (bytes_written, Some(etag_or_last_modified)),
+        };
+
+        let http_range = self.has_http_range(url)?;
+        if http_range {
+            let total = self.get_content_length(url)?;
+            let ranges = Self::build_ranges_from(written, total);
+
+            for batch in ranges.chunks(self.pool_size) {
+                if cancel.load(Ordering::Relaxed) {
+                    return Err(Cancelled.into());
+                }
+
+                // Reorder buffer: workers in a batch may finish in any order, but we join
+                // (and then write) the handles in the same ascending-offset order they were
+                // submitted in, so `writer` always receives bytes in file order.
+                let chunks: Vec<Vec<u8>> = thread::scope(|scope| -> Result<Vec<Vec<u8>>> {
+                    let handles: Vec<_> = batch
+                        .iter()
+                        .map(|&(start, end)| {
+                            let if_range = if_range.as_deref();
+                            scope.spawn(move || {
+                                if cancel.load(Ordering::Relaxed) {
+                                    return Err(Cancelled.into());
+                                }
+                                self.fetch_range_with_retry(url, start, end, cancel, if_range)
+                            })
+                        })
+                        .collect();
+
+                    handles
+                        .into_iter()
+                        .map(|handle| {
+                            handle.join().map_err(|_| {
+                                format_err!("Worker thread panicked while downloading <{}>", url)
+                            })?
+                        })
+                        .collect()
+                })?;
+
+                for data in chunks {
+                    writer.write_all(&data)?;
+                    written += data.len() as u64;
+                    f(written);
+                }
+            }
+            Ok(())
+        } else {
+            if written > 0 {
+                return Err(format_err!(
+                    "Cannot resume <{}>: server does not advertise byte-range support",
+                    url
+                ));
+            }
+            let mut resp = self.send_retrying(|| self.client.get(url), Some(cancel))?;
+            if resp.status().is_success() {
+                let size = resp.copy_to(writer)?;
+                f(size);
+                Ok(())
+            } else {
+                Err(format_err!("Error while getting from url <{}>", url))
+            }
+        }
+    }
+
+    fn get_resource_validator(&self, url: &str) -> Result<Option<String>> {
+        let resp = self.send_retrying(|| self.client.head(url), None)?;
+        Ok(resp
+            .headers()
+            .get(ETAG)
+            .or_else(|| resp.headers().get(LAST_MODIFIED))
+            .and_then(|v| v.to_str().ok())
+            .map(String::from))
+    }
+
     fn post_json(&self, url: &str, json: &Value, auth: &Auth) -> Result<()> {
-        self.client
-            .post(url)
-            .headers(self.construct_headers(auth))
-            .json(json)
-            .send()?;
+        let headers = self.construct_headers(auth);
+        self.send_retrying(|| self.client.post(url).headers(headers.clone()).json(json), None)?;
         Ok(())
     }
 }
 
 impl UdemyHttpClient {
     pub fn new() -> UdemyHttpClient {
-        let client = Client::new();
-        UdemyHttpClient { client }
+        Self::with_config(HttpClientConfig::default())
+            .expect("building the default HTTP client should never fail")
+    }
+
+    /// Builds a client with explicit connect/read timeouts and retry behavior, instead
+    /// of the bare, timeout-less client `new()` used to hand back.
+    pub fn with_config(config: HttpClientConfig) -> Result<UdemyHttpClient> {
+        let client = Client::builder()
+            .connect_timeout(config.connect_timeout)
+            .timeout(config.read_timeout)
+            .build()
+            .map_err(|e| format_err!("Error building HTTP client: {:?}", e))?;
+        Ok(UdemyHttpClient {
+            client,
+            pool_size: DEFAULT_POOL_SIZE,
+            retry: config.retry,
+            cache_dir: config.cache_dir,
+        })
+    }
+
+    /// Number of ranged chunks downloaded concurrently by `get_as_data`.
+    pub fn with_pool_size(mut self, pool_size: usize) -> UdemyHttpClient {
+        self.pool_size = pool_size.max(1);
+        self
+    }
+
+    /// Enables the conditional-request cache used by `get_as_text`/`get_as_json`,
+    /// storing revalidated bodies under `dir`.
+    pub fn with_cache_dir(mut self, dir: impl Into<PathBuf>) -> UdemyHttpClient {
+        self.cache_dir = Some(dir.into());
+        self
+    }
+
+    /// Shared implementation behind `get_as_text`/`get_as_text_bypass_cache`: reads the
+    /// cache (unless `use_cache` is `false`), revalidates it with
+    /// `If-None-Match`/`If-Modified-Since`, and writes a fresh entry back on success.
+    fn get_as_text_with_cache(&self, url: &str, auth: &Auth, use_cache: bool) -> Result<String> {
+        let headers = self.construct_headers(auth);
+        let cached = if use_cache { self.read_cache(url) } else { None };
+        let mut resp = self.send_retrying(|| {
+            let mut builder = self.client.get(url).headers(headers.clone());
+            if let Some(entry) = &cached {
+                if let Some(etag) = &entry.etag {
+                    builder = builder.header(IF_NONE_MATCH, etag.as_str());
+                }
+                if let Some(last_modified) = &entry.last_modified {
+                    builder = builder.header(IF_MODIFIED_SINCE, last_modified.as_str());
+                }
+            }
+            builder
+        }, None)?;
+
+        if resp.status() == StatusCode::NOT_MODIFIED {
+            if let Some(entry) = cached {
+                return Ok(entry.body);
+            }
+        }
+        if resp.status().is_success() {
+            let etag = resp
+                .headers()
+                .get(ETAG)
+                .and_then(|v| v.to_str().ok())
+                .map(String::from);
+            let last_modified = resp
+                .headers()
+                .get(LAST_MODIFIED)
+                .and_then(|v| v.to_str().ok())
+                .map(String::from);
+            let body = resp.text()?;
+            self.write_cache(
+                url,
+                &CacheEntry {
+                    etag,
+                    last_modified,
+                    body: body.clone(),
+                },
+            );
+            Ok(body)
+        } else {
+            Err(format_err!(
+                "Error while getting from url <{}>: <{}>",
+                url,
+                resp.status()
+            ))
+        }
+    }
+
+    fn cache_path(&self, url: &str) -> Option<PathBuf> {
+        let dir = self.cache_dir.as_ref()?;
+        let mut hasher = DefaultHasher::new();
+        url.hash(&mut hasher);
+        Some(dir.join(format!("{:016x}.json", hasher.finish())))
+    }
+
+    fn read_cache(&self, url: &str) -> Option<CacheEntry> {
+        let data = fs::read_to_string(self.cache_path(url)?).ok()?;
+        from_str(&data).ok()
+    }
+
+    fn write_cache(&self, url: &str, entry: &CacheEntry) {
+        let path = match self.cache_path(url) {
+            Some(path) => path,
+            None => return,
+        };
+        if let Some(dir) = path.parent() {
+            if fs::create_dir_all(dir).is_ok() {
+                if let Ok(data) = serde_json::to_string(entry) {
+                    let _ = fs::write(path, data);
+                }
+            }
+        }
+    }
+
+    /// Drops the cached entry for `url`, forcing the next `get_as_text`/`get_as_json`
+    /// call to refetch it.
+    pub fn invalidate_cache(&self, url: &str) -> Result<()> {
+        if let Some(path) = self.cache_path(url) {
+            if path.exists() {
+                fs::remove_file(&path)
+                    .map_err(|e| format_err!("Error removing cache entry <{:?}>: {:?}", path, e))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Drops every cached entry, e.g. to force a full refetch of a catalog.
+    pub fn clear_cache(&self) -> Result<()> {
+        if let Some(dir) = &self.cache_dir {
+            if dir.exists() {
+                fs::remove_dir_all(dir)
+                    .map_err(|e| format_err!("Error clearing cache dir <{:?}>: {:?}", dir, e))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Sends the request built by `build`, retrying on connection errors, timeouts, and
+    /// `429`/`5xx` responses according to `self.retry`. Honors a `Retry-After` header on
+    /// throttled responses. Any other status (success or a non-retryable error) is
+    /// returned as-is for the caller to interpret.
+    ///
+    /// `cancel`, when given, is checked before every attempt, including each retry once
+    /// its backoff sleep has elapsed, so a long string of retries can still be aborted.
+    fn send_retrying(
+        &self,
+        mut build: impl FnMut() -> reqwest::RequestBuilder,
+        cancel: Option<&CancelToken>,
+    ) -> Result<Response> {
+        let mut attempt = 0;
+        loop {
+            if cancel.map_or(false, |c| c.load(Ordering::Relaxed)) {
+                return Err(Cancelled.into());
+            }
+            match build().send() {
+                Ok(resp) => {
+                    let status = resp.status();
+                    let retryable = status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS;
+                    if !retryable || attempt + 1 >= self.retry.max_attempts {
+                        return Ok(resp);
+                    }
+                    thread::sleep(Self::retry_after(&resp).unwrap_or_else(|| self.retry.delay_for(attempt)));
+                }
+                Err(e) => {
+                    // `status()` is `None` for errors that never got a response at all
+                    // (connection refused/reset, DNS failure, ...), as well as timeouts.
+                    let retryable = e.is_timeout() || e.status().is_none();
+                    if !retryable || attempt + 1 >= self.retry.max_attempts {
+                        return Err(e.into());
+                    }
+                    thread::sleep(self.retry.delay_for(attempt));
+                }
+            }
+            attempt += 1;
+        }
+    }
+
+    fn retry_after(resp: &Response) -> Option<Duration> {
+        resp.headers()
+            .get(RETRY_AFTER)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|s| s.parse::<u64>().ok())
+            .map(Duration::from_secs)
+    }
+
+    /// Splits `[0, total)` into `CHUNK`-sized `[start, end]` byte ranges (inclusive end).
+    fn build_ranges(total: u64) -> Vec<(u64, u64)> {
+        Self::build_ranges_from(0, total)
+    }
+
+    /// Splits `[start, total)` into `CHUNK`-sized `[start, end]` byte ranges (inclusive
+    /// end); used by `get_as_data_to` to resume from an arbitrary offset.
+    fn build_ranges_from(start: u64, total: u64) -> Vec<(u64, u64)> {
+        let mut ranges = Vec::new();
+        let mut offset = start;
+        while offset < total {
+            let end = (offset + CHUNK - 1).min(total - 1);
+            ranges.push((offset, end));
+            offset += CHUNK;
+        }
+        ranges
+    }
+
+    /// Fetches `[start, end]` (inclusive) of `url`. The request itself goes through
+    /// `send_retrying`, so connection errors, timeouts, and `429`/`5xx` responses are
+    /// retried (honoring `Retry-After`) exactly like every other request this client
+    /// makes. A response that isn't an honest `206` — wrong status, or a body that
+    /// doesn't match the requested length — is a permanent failure (retrying wouldn't
+    /// change a CDN's decision to ignore `Range`) and is returned as an error without
+    /// retrying it further.
+    fn fetch_range_with_retry(
+        &self,
+        url: &str,
+        start: u64,
+        end: u64,
+        cancel: &CancelToken,
+        if_range: Option<&str>,
+    ) -> Result<Vec<u8>> {
+        let mut resp = self.send_retrying(
+            || {
+                let mut builder = self
+                    .client
+                    .get(url)
+                    .header(RANGE, format!("bytes={}-{}", start, end));
+                if let Some(validator) = if_range {
+                    builder = builder.header(IF_RANGE, validator);
+                }
+                builder
+            },
+            Some(cancel),
+        )?;
+        match resp.status() {
+            // Only `206` proves the server actually honored the `Range` header. A `200`
+            // here means it ignored it and sent the whole body back, which would silently
+            // misplace bytes once indexed into the destination buffer at `start` — treat
+            // it as a hard error instead.
+            StatusCode::PARTIAL_CONTENT => {
+                let expected = (end - start + 1) as usize;
+                let mut buf = Vec::with_capacity(expected);
+                resp.copy_to(&mut buf)?;
+                if buf.len() != expected {
+                    return Err(format_err!(
+                        "Expected {} bytes for range {}-{} of url <{}>, got {}",
+                        expected,
+                        start,
+                        end,
+                        url,
+                        buf.len()
+                    ));
+                }
+                Ok(buf)
+            }
+            status => Err(format_err!(
+                "Server did not honor range request ({:?}) for range {}-{} of url <{}>",
+                status,
+                start,
+                end,
+                url
+            )),
+        }
     }
 
     fn has_http_range(&self, url: &str) -> Result<bool> {
-        self.client
-            .head(url)
-            .send()
-            .map(|res| res.headers().contains_key(ACCEPT_RANGES))
-            .map_err(|_e| format_err!("Could not check http range"))
+        let resp = self.send_retrying(|| self.client.head(url), None)?;
+        Ok(resp.headers().contains_key(ACCEPT_RANGES))
     }
 
     fn construct_headers(&self, auth: &Auth) -> HeaderMap {
@@ -153,3 +650,450 @@ impl UdemyHttpClient {
         headers
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicUsize;
+
+    #[test]
+    fn build_ranges_splits_into_chunk_sized_pieces() {
+        let ranges = UdemyHttpClient::build_ranges(CHUNK * 2);
+        assert_eq!(ranges, vec![(0, CHUNK - 1), (CHUNK, CHUNK * 2 - 1)]);
+    }
+
+    #[test]
+    fn build_ranges_last_chunk_is_truncated_to_total() {
+        let total = CHUNK + 10;
+        let ranges = UdemyHttpClient::build_ranges(total);
+        assert_eq!(ranges, vec![(0, CHUNK - 1), (CHUNK, total - 1)]);
+    }
+
+    #[test]
+    fn build_ranges_exact_multiple_has_no_trailing_empty_range() {
+        let total = CHUNK * 3;
+        let ranges = UdemyHttpClient::build_ranges(total);
+        assert_eq!(ranges.len(), 3);
+        assert_eq!(ranges.last(), Some(&(CHUNK * 2, total - 1)));
+    }
+
+    #[test]
+    fn build_ranges_of_zero_is_empty() {
+        assert_eq!(UdemyHttpClient::build_ranges(0), vec![]);
+    }
+
+    #[test]
+    fn build_ranges_smaller_than_a_chunk_is_a_single_range() {
+        assert_eq!(UdemyHttpClient::build_ranges(10), vec![(0, 9)]);
+    }
+
+    #[test]
+    fn build_ranges_from_resumes_at_the_given_offset() {
+        let total = CHUNK * 2;
+        let ranges = UdemyHttpClient::build_ranges_from(CHUNK, total);
+        assert_eq!(ranges, vec![(CHUNK, total - 1)]);
+    }
+
+    #[test]
+    fn build_ranges_from_at_total_is_empty() {
+        assert_eq!(UdemyHttpClient::build_ranges_from(CHUNK, CHUNK), vec![]);
+    }
+
+    #[test]
+    fn delay_for_grows_exponentially_before_hitting_the_cap() {
+        let policy = RetryPolicy {
+            max_attempts: 10,
+            base_delay: Duration::from_millis(100),
+            max_delay: Duration::from_secs(10),
+        };
+        // Jitter only ever adds up to `capped_ms`, so the un-jittered exponential value
+        // is a lower bound and double that is an upper bound.
+        for attempt in 0..5 {
+            let expected_floor = Duration::from_millis(100 * 2u64.pow(attempt));
+            let delay = policy.delay_for(attempt);
+            assert!(
+                delay >= expected_floor,
+                "attempt {}: {:?} should be >= {:?}",
+                attempt,
+                delay,
+                expected_floor
+            );
+            assert!(
+                delay <= expected_floor * 2,
+                "attempt {}: {:?} should be <= {:?}",
+                attempt,
+                delay,
+                expected_floor * 2
+            );
+        }
+    }
+
+    #[test]
+    fn delay_for_is_capped_at_max_delay() {
+        let policy = RetryPolicy {
+            max_attempts: 10,
+            base_delay: Duration::from_millis(100),
+            max_delay: Duration::from_secs(1),
+        };
+        // A large attempt number would overflow without the cap; jitter can add at most
+        // `max_delay` more on top of the capped value.
+        let delay = policy.delay_for(63);
+        assert!(delay >= Duration::from_secs(1));
+        assert!(delay <= Duration::from_secs(2));
+    }
+
+    #[test]
+    fn delay_for_jitter_stays_within_bounds() {
+        let policy = RetryPolicy {
+            max_attempts: 10,
+            base_delay: Duration::from_millis(50),
+            max_delay: Duration::from_secs(10),
+        };
+        for attempt in 0..8 {
+            let base = Duration::from_millis(50 * 2u64.pow(attempt));
+            let delay = policy.delay_for(attempt);
+            assert!(delay >= base);
+            assert!(delay < base * 2);
+        }
+    }
+
+    /// Bare-bones `HEAD`/ranged-`GET` server so `get_as_data` can be exercised without a
+    /// real network dependency. Every connection gets exactly one response and is then
+    /// closed, which is all `get_as_data`'s blocking client needs. Returns the base URL,
+    /// a shutdown flag, and a count of ranged `GET`s actually served, so callers can
+    /// assert on how much work the server saw rather than just on an error existing.
+    fn spawn_mock_range_server(total: u64) -> (String, Arc<AtomicBool>, Arc<AtomicUsize>) {
+        use std::io::Read;
+        use std::net::TcpListener;
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.set_nonblocking(true).unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let shutdown_clone = shutdown.clone();
+        let get_count = Arc::new(AtomicUsize::new(0));
+        let get_count_clone = get_count.clone();
+
+        thread::spawn(move || {
+            while !shutdown_clone.load(Ordering::Relaxed) {
+                match listener.accept() {
+                    Ok((mut stream, _)) => {
+                        stream.set_nonblocking(false).unwrap();
+                        let mut buf = [0_u8; 4096];
+                        let n = stream.read(&mut buf).unwrap_or(0);
+                        let request = String::from_utf8_lossy(&buf[..n]);
+
+                        if request.starts_with("HEAD") {
+                            let _ = stream.write_all(
+                                format!(
+                                    "HTTP/1.1 200 OK\r\nAccept-Ranges: bytes\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                                    total
+                                )
+                                .as_bytes(),
+                            );
+                        } else {
+                            // Header names/values arrive as sent on the wire, which for
+                            // reqwest's standard `RANGE` header is lowercase (`range: ...`),
+                            // not the title-cased form HTTP examples often show — match
+                            // case-insensitively or this never finds the header at all.
+                            let (start, end) = request
+                                .lines()
+                                .find_map(|l| {
+                                    l.to_ascii_lowercase()
+                                        .strip_prefix("range: bytes=")
+                                        .map(str::to_string)
+                                })
+                                .and_then(|r| r.trim().split_once('-').map(|(s, e)| (s.to_string(), e.to_string())))
+                                .map(|(s, e)| (s.parse::<u64>().unwrap(), e.parse::<u64>().unwrap()))
+                                .unwrap();
+                            get_count_clone.fetch_add(1, Ordering::Relaxed);
+                            let body = vec![0_u8; (end - start + 1) as usize];
+                            let _ = stream.write_all(
+                                format!(
+                                    "HTTP/1.1 206 Partial Content\r\nContent-Range: bytes {}-{}/{}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                                    start, end, total, body.len()
+                                )
+                                .as_bytes(),
+                            );
+                            let _ = stream.write_all(&body);
+                        }
+                    }
+                    Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => {
+                        thread::sleep(Duration::from_millis(5));
+                    }
+                    Err(_) => break,
+                }
+            }
+        });
+
+        (format!("http://127.0.0.1:{}", port), shutdown, get_count)
+    }
+
+    #[test]
+    fn get_as_data_stops_between_batches_and_discards_buf_when_cancelled() {
+        // Three ranges with `pool_size(1)` means three sequential batches; cancelling
+        // from inside the progress callback after the first range completes must stop
+        // the client before the second or third range is ever requested.
+        let total = CHUNK * 3;
+        let (url, shutdown, get_count) = spawn_mock_range_server(total);
+        let client = UdemyHttpClient::new().with_pool_size(1);
+        let cancel = new_cancel_token();
+
+        let cancel_for_callback = cancel.clone();
+        let result = client.get_as_data(&url, &cancel, &move |_| {
+            cancel_for_callback.store(true, Ordering::Relaxed);
+        });
+
+        shutdown.store(true, Ordering::Relaxed);
+        assert!(result.is_err(), "cancelled download should not return data");
+        assert_eq!(
+            get_count.load(Ordering::Relaxed),
+            1,
+            "cancellation should stop the client after the first range, not fetch all of them"
+        );
+    }
+
+    /// Minimal server for the `get_as_text` cache round trip: the first request gets a
+    /// `200` with an `ETag` and `body`. Any request that correctly carries that `ETag`
+    /// back via `If-None-Match` gets a bodyless `304`; any request that doesn't gets a
+    /// fresh `200` with `stale_body` instead of `body`, so a cache bug that fails to
+    /// revalidate (or a test that doesn't actually check the 304 path) shows up as a
+    /// body mismatch rather than passing by coincidence.
+    fn spawn_mock_cache_server(
+        body: &'static str,
+        stale_body: &'static str,
+        etag: &'static str,
+    ) -> (String, Arc<AtomicBool>) {
+        use std::io::Read;
+        use std::net::TcpListener;
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.set_nonblocking(true).unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let shutdown_clone = shutdown.clone();
+
+        thread::spawn(move || {
+            let mut requests_seen = 0_usize;
+            while !shutdown_clone.load(Ordering::Relaxed) {
+                match listener.accept() {
+                    Ok((mut stream, _)) => {
+                        stream.set_nonblocking(false).unwrap();
+                        let mut buf = [0_u8; 4096];
+                        let n = stream.read(&mut buf).unwrap_or(0);
+                        let request = String::from_utf8_lossy(&buf[..n]);
+                        // Header names arrive lowercased on the wire (`if-none-match: ...`),
+                        // not the title-cased form HTTP examples often show.
+                        let revalidated = request.lines().any(|l| {
+                            l.to_ascii_lowercase()
+                                .trim_start_matches("if-none-match:")
+                                .trim()
+                                == etag
+                        });
+                        requests_seen += 1;
+
+                        if revalidated {
+                            let _ = stream.write_all(
+                                b"HTTP/1.1 304 Not Modified\r\nConnection: close\r\n\r\n",
+                            );
+                        } else {
+                            let served = if requests_seen == 1 { body } else { stale_body };
+                            let _ = stream.write_all(
+                                format!(
+                                    "HTTP/1.1 200 OK\r\nETag: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                                    etag,
+                                    served.len(),
+                                    served
+                                )
+                                .as_bytes(),
+                            );
+                        }
+                    }
+                    Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => {
+                        thread::sleep(Duration::from_millis(5));
+                    }
+                    Err(_) => break,
+                }
+            }
+        });
+
+        (format!("http://127.0.0.1:{}", port), shutdown)
+    }
+
+    #[test]
+    fn get_as_text_revalidates_via_etag_and_serves_cached_body_on_304() {
+        let body = "hello from cache";
+        let stale_body = "SHOULD NOT BE SERVED ON A CACHE HIT";
+        let etag = "\"abc123\"";
+        let (url, shutdown) = spawn_mock_cache_server(body, stale_body, etag);
+
+        let cache_dir = std::env::temp_dir().join(format!(
+            "udemy-dl-rs-test-cache-{:?}-{}",
+            thread::current().id(),
+            jitter_ms(1_000_000)
+        ));
+        let client = UdemyHttpClient::with_config(HttpClientConfig {
+            cache_dir: Some(cache_dir.clone()),
+            ..HttpClientConfig::default()
+        })
+        .unwrap();
+        let auth = Auth {
+            access_token: Some("test-token".to_string()),
+            ..Auth::default()
+        };
+
+        let first = client.get_as_text(&url, &auth).unwrap();
+        assert_eq!(first, body);
+        assert!(client.cache_path(&url).unwrap().exists());
+
+        // The server only ever serves `body` once; if `get_as_text` doesn't send
+        // `If-None-Match` (or the cache isn't consulted at all), this call gets
+        // `stale_body` back from a fresh `200` instead of the cached `body` from a 304,
+        // and the assertion below catches it.
+        let second = client.get_as_text(&url, &auth).unwrap();
+        assert_eq!(second, body);
+
+        shutdown.store(true, Ordering::Relaxed);
+        let _ = fs::remove_dir_all(&cache_dir);
+    }
+
+    /// Server for `get_as_data_to`/`Resume` coverage. `HEAD` always reports `total` and
+    /// `Accept-Ranges`. A ranged `GET` honors the range (`206`) unless it carries an
+    /// `If-Range` value that doesn't match `validator`, in which case it behaves like a
+    /// server that saw the resource change: it ignores `Range` entirely and sends the
+    /// full body back as a plain `200`, same as a real CDN would.
+    fn spawn_mock_resumable_server(total: u64, validator: &'static str) -> (String, Arc<AtomicBool>) {
+        use std::io::Read;
+        use std::net::TcpListener;
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.set_nonblocking(true).unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let shutdown_clone = shutdown.clone();
+
+        thread::spawn(move || {
+            while !shutdown_clone.load(Ordering::Relaxed) {
+                match listener.accept() {
+                    Ok((mut stream, _)) => {
+                        stream.set_nonblocking(false).unwrap();
+                        let mut buf = [0_u8; 4096];
+                        let n = stream.read(&mut buf).unwrap_or(0);
+                        let request = String::from_utf8_lossy(&buf[..n]);
+
+                        if request.starts_with("HEAD") {
+                            let _ = stream.write_all(
+                                format!(
+                                    "HTTP/1.1 200 OK\r\nAccept-Ranges: bytes\r\nETag: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                                    validator, total
+                                )
+                                .as_bytes(),
+                            );
+                            continue;
+                        }
+
+                        let lower = request.to_ascii_lowercase();
+                        let (start, end) = lower
+                            .lines()
+                            .find_map(|l| l.strip_prefix("range: bytes=").map(str::to_string))
+                            .and_then(|r| r.trim().split_once('-').map(|(s, e)| (s.to_string(), e.to_string())))
+                            .map(|(s, e)| (s.parse::<u64>().unwrap(), e.parse::<u64>().unwrap()))
+                            .unwrap();
+                        let if_range_matches = lower
+                            .lines()
+                            .find_map(|l| l.strip_prefix("if-range:").map(|v| v.trim().to_string()))
+                            .map(|v| v == validator.to_ascii_lowercase())
+                            .unwrap_or(true);
+
+                        if if_range_matches {
+                            let body = vec![0_u8; (end - start + 1) as usize];
+                            let _ = stream.write_all(
+                                format!(
+                                    "HTTP/1.1 206 Partial Content\r\nContent-Range: bytes {}-{}/{}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                                    start, end, total, body.len()
+                                )
+                                .as_bytes(),
+                            );
+                            let _ = stream.write_all(&body);
+                        } else {
+                            // Resource changed since the caller captured `validator`: the
+                            // server ignores `Range` and sends the whole thing back.
+                            let body = vec![0_u8; total as usize];
+                            let _ = stream.write_all(
+                                format!(
+                                    "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                                    body.len()
+                                )
+                                .as_bytes(),
+                            );
+                            let _ = stream.write_all(&body);
+                        }
+                    }
+                    Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => {
+                        thread::sleep(Duration::from_millis(5));
+                    }
+                    Err(_) => break,
+                }
+            }
+        });
+
+        (format!("http://127.0.0.1:{}", port), shutdown)
+    }
+
+    #[test]
+    fn get_as_data_to_resumes_from_offset_with_matching_validator() {
+        let total = CHUNK + 500;
+        let validator = "\"still-fresh\"";
+        let (url, shutdown) = spawn_mock_resumable_server(total, validator);
+        let client = UdemyHttpClient::new().with_pool_size(1);
+        let cancel = new_cancel_token();
+
+        let mut written_bytes = Vec::new();
+        let last_progress = Arc::new(AtomicU64::new(0));
+        let last_progress_clone = last_progress.clone();
+        let result = client.get_as_data_to(
+            &url,
+            &cancel,
+            &mut written_bytes,
+            Resume::Resumed {
+                bytes_written: CHUNK,
+                etag_or_last_modified: validator.to_string(),
+            },
+            &move |n| last_progress_clone.store(n, Ordering::Relaxed),
+        );
+
+        shutdown.store(true, Ordering::Relaxed);
+        result.unwrap();
+        // Only the unfetched tail (from `CHUNK` to `total`) should have been written —
+        // resuming must not re-download or re-write bytes already on disk.
+        assert_eq!(written_bytes.len(), (total - CHUNK) as usize);
+        assert_eq!(last_progress.load(Ordering::Relaxed), total);
+    }
+
+    #[test]
+    fn get_as_data_to_rejects_resume_when_validator_no_longer_matches() {
+        let total = CHUNK + 500;
+        let server_validator = "\"current-etag\"";
+        let (url, shutdown) = spawn_mock_resumable_server(total, server_validator);
+        let client = UdemyHttpClient::new().with_pool_size(1);
+        let cancel = new_cancel_token();
+
+        let mut written_bytes = Vec::new();
+        let result = client.get_as_data_to(
+            &url,
+            &cancel,
+            &mut written_bytes,
+            Resume::Resumed {
+                bytes_written: CHUNK,
+                etag_or_last_modified: "\"stale-etag-from-a-previous-run\"".to_string(),
+            },
+            &|_| {},
+        );
+
+        shutdown.store(true, Ordering::Relaxed);
+        assert!(
+            result.is_err(),
+            "a resume whose If-Range no longer matches must not silently splice stale and fresh bytes"
+        );
+    }
+}